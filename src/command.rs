@@ -1,5 +1,6 @@
-use std::sync::Arc;
+use std::{str, sync::Arc};
 
+use bytes::Bytes;
 use thiserror::Error;
 
 use crate::protocol::ProtocolData;
@@ -22,7 +23,7 @@ pub enum Expire {
 #[derive(Debug, Default)]
 pub struct SetOpts {
     pub key: Arc<str>,
-    pub val: Arc<str>,
+    pub val: Bytes,
     pub cond: Option<SetCond>,
     pub ret_old: bool,
     pub expire: Option<Expire>,
@@ -31,9 +32,12 @@ pub struct SetOpts {
 #[derive(Debug)]
 pub enum Command {
     Ping,
-    Echo(Arc<str>),
+    Echo(Bytes),
     Set(SetOpts),
     Get(Arc<str>),
+    Subscribe(Vec<Arc<str>>),
+    Unsubscribe(Vec<Arc<str>>),
+    Publish { channel: Arc<str>, message: Bytes },
 }
 
 #[derive(Debug, Error)]
@@ -44,13 +48,15 @@ pub enum ParseCommandError {
     WrongProtocolDataType,
     #[error("Wrong argument type or number")]
     WrongArguments,
+    #[error("Key or channel name is not valid UTF-8")]
+    InvalidKeyEncoding,
 }
 
-fn parse_command_like(prot: &ProtocolData) -> Result<(Arc<str>, Vec<Arc<str>>), ParseCommandError> {
+fn parse_command_like(prot: &ProtocolData) -> Result<(Arc<str>, Vec<Bytes>), ParseCommandError> {
     match prot {
         ProtocolData::Array(v) => match v[0] {
             ProtocolData::BulkString(ref s) => {
-                let cmd = Arc::from(s.to_uppercase());
+                let cmd = Arc::from(String::from_utf8_lossy(s).to_uppercase());
                 let mut args = Vec::with_capacity(v.len() - 1);
 
                 for i in 1..v.len() {
@@ -69,6 +75,22 @@ fn parse_command_like(prot: &ProtocolData) -> Result<(Arc<str>, Vec<Arc<str>>),
     }
 }
 
+/// Command-name/flag tokens (e.g. `XX`, `EX`) are plain ASCII, so lossy
+/// decoding is fine here even though argument payloads are binary-safe.
+fn arg_str(arg: &Bytes) -> String {
+    String::from_utf8_lossy(arg).to_uppercase()
+}
+
+/// Unlike `arg_str`, this must preserve the argument byte-for-byte: it's
+/// used for map keys and channel names, where lossily mapping two distinct
+/// binary keys onto the same replacement character would silently merge
+/// them in the keyspace.
+fn key_str(arg: &Bytes) -> Result<Arc<str>, ParseCommandError> {
+    str::from_utf8(arg)
+        .map(Arc::from)
+        .map_err(|_| ParseCommandError::InvalidKeyEncoding)
+}
+
 pub fn parse_command(prot: ProtocolData) -> Result<Command, ParseCommandError> {
     let (cmd, args) = parse_command_like(&prot)?;
     match cmd.as_ref() {
@@ -80,28 +102,28 @@ pub fn parse_command(prot: ProtocolData) -> Result<Command, ParseCommandError> {
             }
         }
         "ECHO" => match args.len() {
-            1 => Ok(Command::Echo(args[0].to_owned())),
+            1 => Ok(Command::Echo(args[0].clone())),
             _ => Err(ParseCommandError::WrongArguments),
         },
         "GET" => match args.len() {
-            1 => Ok(Command::Get(args[0].to_owned())),
+            1 => Ok(Command::Get(key_str(&args[0])?)),
             _ => Err(ParseCommandError::WrongArguments),
         },
         "SET" => match args.len() {
             len if len >= 2 => {
                 let mut opts = SetOpts::default();
-                opts.key = args[0].clone();
+                opts.key = key_str(&args[0])?;
                 opts.val = args[1].clone();
 
                 let mut idx = 2;
                 while idx < args.len() {
-                    match args[idx].to_uppercase().as_str() {
+                    match arg_str(&args[idx]).as_str() {
                         "GET" => {
                             opts.ret_old = true;
                             idx += 1;
                         }
                         "XX" | "NX" if opts.cond.is_none() => {
-                            match args[idx].as_ref() {
+                            match arg_str(&args[idx]).as_str() {
                                 "XX" => opts.cond = Some(SetCond::XX),
                                 "NX" => opts.cond = Some(SetCond::NX),
                                 _ => unreachable!(),
@@ -115,9 +137,10 @@ pub fn parse_command(prot: ProtocolData) -> Result<Command, ParseCommandError> {
                         "EX" | "PX" | "EXAT" | "PXAT"
                             if opts.expire.is_none() && idx + 1 < args.len() =>
                         {
-                            let tval = u64::from_str_radix(args[idx + 1].as_ref(), 10)
+                            let tval = String::from_utf8_lossy(&args[idx + 1])
+                                .parse::<u64>()
                                 .map_err(|_| ParseCommandError::WrongArguments)?;
-                            match args[idx].as_ref() {
+                            match arg_str(&args[idx]).as_str() {
                                 "EX" => opts.expire = Some(Expire::EX(tval)),
                                 "PX" => opts.expire = Some(Expire::PX(tval)),
                                 "EXAT" => opts.expire = Some(Expire::EXAT(tval)),
@@ -133,6 +156,25 @@ pub fn parse_command(prot: ProtocolData) -> Result<Command, ParseCommandError> {
             }
             _ => Err(ParseCommandError::WrongArguments),
         },
+        "SUBSCRIBE" => {
+            if args.is_empty() {
+                Err(ParseCommandError::WrongArguments)
+            } else {
+                Ok(Command::Subscribe(
+                    args.iter().map(key_str).collect::<Result<_, _>>()?,
+                ))
+            }
+        }
+        "UNSUBSCRIBE" => Ok(Command::Unsubscribe(
+            args.iter().map(key_str).collect::<Result<_, _>>()?,
+        )),
+        "PUBLISH" => match args.len() {
+            2 => Ok(Command::Publish {
+                channel: key_str(&args[0])?,
+                message: args[1].clone(),
+            }),
+            _ => Err(ParseCommandError::WrongArguments),
+        },
         _ => Err(ParseCommandError::UnrecognizedCommand(cmd.to_owned())),
     }
 }
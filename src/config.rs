@@ -0,0 +1,93 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arc_swap::ArcSwap;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Config file format version, bumped whenever the schema changes so
+    /// future releases can migrate old files instead of rejecting them.
+    pub version: String,
+    pub bind: String,
+    pub data_dir: PathBuf,
+    pub max_connections: usize,
+    pub default_ttl: Option<u64>,
+    /// Require the ChaCha20-Poly1305 + X25519 encrypted frame layer for new
+    /// connections instead of plaintext RESP. Defaults to off so existing
+    /// plaintext deployments keep working untouched.
+    #[serde(default)]
+    pub encryption: bool,
+    /// How often, in seconds, the background task snapshots the keyspace to
+    /// `data_dir`. A snapshot is also written on graceful shutdown. `0`
+    /// disables periodic snapshotting.
+    #[serde(default = "default_snapshot_interval_secs")]
+    pub snapshot_interval_secs: u64,
+}
+
+fn default_snapshot_interval_secs() -> u64 {
+    300
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: "1".to_string(),
+            bind: "127.0.0.1:6379".to_string(),
+            data_dir: PathBuf::from("./data"),
+            max_connections: 10_000,
+            default_ttl: None,
+            encryption: false,
+            snapshot_interval_secs: default_snapshot_interval_secs(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let raw = std::fs::read_to_string(path)?;
+        toml::from_str(&raw).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Watches `path` for changes and atomically swaps `live` with the
+/// reloaded `Config` on every write, so operators can retune the server
+/// without restarting it. Only settings read from `live` after startup take
+/// effect on reload (`default_ttl`, `encryption`, and `data_dir` for
+/// `Redis::snapshot_now`); `bind`, `max_connections` and
+/// `snapshot_interval_secs` are read once at startup and keep their startup
+/// values. The returned watcher must be kept alive for the lifetime of the
+/// server; dropping it stops the watch.
+pub fn spawn_config_watcher_system(
+    path: PathBuf,
+    live: Arc<ArcSwap<Config>>,
+) -> notify::Result<RecommendedWatcher> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            if matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_)) {
+                let _ = tx.send(());
+            }
+        }
+    })?;
+    watcher.watch(&path, RecursiveMode::NonRecursive)?;
+
+    tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            match Config::from_file(&path) {
+                Ok(cfg) => {
+                    println!("config reloaded from {}", path.display());
+                    live.store(Arc::new(cfg));
+                }
+                Err(e) => eprintln!("failed to reload config {}: {}", path.display(), e),
+            }
+        }
+    });
+
+    Ok(watcher)
+}
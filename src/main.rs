@@ -1,26 +1,83 @@
 mod command;
+mod config;
 mod protocol;
+mod pubsub;
 mod redis;
+mod transport;
 
-use std::{io, sync::Arc};
+use std::{io, path::PathBuf, sync::Arc};
 
+use arc_swap::ArcSwap;
+use config::{Config, spawn_config_watcher_system};
 use redis::Redis;
-use tokio::net::TcpListener;
+use tokio::{net::TcpListener, sync::Semaphore};
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:6379").await?;
-    let redis: Arc<Redis> = Arc::new(Redis::new());
+    let config_path = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("config.toml"));
 
-    loop {
-        match listener.accept().await {
-            Ok((s, addr)) => {
-                println!("accepted new connection from {}", addr);
-                let rc = Arc::clone(&redis);
-                let _ = tokio::spawn(async move { rc.handler(s).await });
-            }
+    let config = match Config::from_file(&config_path) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            println!(
+                "no usable config at {} ({}), falling back to defaults",
+                config_path.display(),
+                e
+            );
+            Config::default()
+        }
+    };
+    let bind = config.bind.clone();
+    let max_connections = config.max_connections;
+    let live_config = Arc::new(ArcSwap::from_pointee(config));
+
+    // `notify` stats the path before watching it, so a nonexistent config
+    // file (the zero-config path above just fell back from) would only make
+    // the watch fail; skip the watcher in that case instead.
+    let _watcher = if config_path.exists() {
+        match spawn_config_watcher_system(config_path, Arc::clone(&live_config)) {
+            Ok(watcher) => Some(watcher),
             Err(e) => {
-                println!("error: {}", e);
+                eprintln!("failed to watch config file: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let listener = TcpListener::bind(&bind).await?;
+    let redis: Arc<Redis> = Arc::new(Redis::new(Arc::clone(&live_config)));
+    let conn_limit = Arc::new(Semaphore::new(max_connections));
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((s, addr)) => {
+                    let Ok(permit) = Arc::clone(&conn_limit).try_acquire_owned() else {
+                        println!("rejected connection from {}: max connections reached", addr);
+                        continue;
+                    };
+                    println!("accepted new connection from {}", addr);
+                    let rc = Arc::clone(&redis);
+                    let _ = tokio::spawn(async move {
+                        rc.handler(s).await;
+                        drop(permit);
+                    });
+                }
+                Err(e) => {
+                    println!("error: {}", e);
+                    break;
+                }
+            },
+            _ = tokio::signal::ctrl_c() => {
+                println!("shutting down, writing final snapshot");
+                if let Err(e) = redis.snapshot_now() {
+                    eprintln!("failed to write shutdown snapshot: {}", e);
+                }
                 break;
             }
         }
@@ -1,10 +1,10 @@
-use std::str::FromStr;
+use std::str::{self, FromStr};
 
 use anyhow::anyhow;
+use bytes::Bytes;
 use nom::{
     IResult, Parser,
-    bytes::streaming::{tag, take_until},
-    character::streaming::{anychar, one_of},
+    bytes::streaming::{tag, take, take_until},
     combinator::{map, map_res, peek},
 };
 
@@ -13,151 +13,242 @@ pub enum ProtocolData {
     SimpleString(String),
     SimpleError(String),
     Integer(i64),
-    BulkString(String),
+    BulkString(Bytes),
     Array(Vec<ProtocolData>),
     Null,
     Boolean(bool),
     Double(f64),
     BigNums(String),
-    BulkError(String),
-    Verbatim(String, String),
+    BulkError(Bytes),
+    Verbatim(String, Bytes),
     Map(Vec<(ProtocolData, ProtocolData)>),
     Attributes(Vec<(ProtocolData, ProtocolData)>),
     Set(Vec<ProtocolData>),
     Push(Vec<ProtocolData>),
 }
 
-pub fn parse_protocol(s: &str) -> IResult<&str, ProtocolData> {
-    match peek(anychar).parse(s)?.1 {
-        '+' => parse_simple_string(s),
-        '-' => parse_simple_error(s),
-        ':' => parse_integer(s),
-        '$' => parse_bulk_string(s),
-        '*' => parse_array(s),
-        '_' => parse_null(s),
-        '#' => parse_boolean(s),
-        ',' => parse_doubles(s),
-        '(' => parse_bignum(s),
-        '!' => parse_bulk_error(s),
-        '=' => parse_verbatim(s),
-        '%' => parse_map(s),
-        '|' => parse_attributes(s),
-        '~' => parse_set(s),
-        '>' => parse_push(s),
+pub fn parse_protocol(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    match peek(take(1usize)).parse(s)?.1[0] {
+        b'+' => parse_simple_string(s),
+        b'-' => parse_simple_error(s),
+        b':' => parse_integer(s),
+        b'$' => parse_bulk_string(s),
+        b'*' => parse_array(s),
+        b'_' => parse_null(s),
+        b'#' => parse_boolean(s),
+        b',' => parse_doubles(s),
+        b'(' => parse_bignum(s),
+        b'!' => parse_bulk_error(s),
+        b'=' => parse_verbatim(s),
+        b'%' => parse_map(s),
+        b'|' => parse_attributes(s),
+        b'~' => parse_set(s),
+        b'>' => parse_push(s),
         _ => unimplemented!(),
     }
 }
 
-pub fn encode_protocol(prot: ProtocolData) -> String {
+pub fn encode_protocol(prot: ProtocolData) -> Vec<u8> {
     match prot {
-        ProtocolData::SimpleString(s) => format!("+{}\r\n", &s),
-        ProtocolData::SimpleError(s) => format!("-{}\r\n", &s),
-        ProtocolData::Integer(v) => format!(":{}\r\n", v),
-        ProtocolData::BulkString(s) => format!("${}\r\n{}\r\n", s.len(), s),
-        _ => unimplemented!(),
+        ProtocolData::SimpleString(s) => format!("+{}\r\n", &s).into_bytes(),
+        ProtocolData::SimpleError(s) => format!("-{}\r\n", &s).into_bytes(),
+        ProtocolData::Integer(v) => format!(":{}\r\n", v).into_bytes(),
+        ProtocolData::BulkString(b) => {
+            let mut out = format!("${}\r\n", b.len()).into_bytes();
+            out.extend_from_slice(&b);
+            out.extend_from_slice(b"\r\n");
+            out
+        }
+        ProtocolData::Array(items) => encode_aggregate(b'*', items),
+        ProtocolData::Push(items) => encode_aggregate(b'>', items),
+        ProtocolData::Set(items) => encode_aggregate(b'~', items),
+        ProtocolData::Null => b"_\r\n".to_vec(),
+        ProtocolData::Boolean(b) => format!("#{}\r\n", if b { 't' } else { 'f' }).into_bytes(),
+        ProtocolData::Double(v) => format!(",{}\r\n", format_double(v)).into_bytes(),
+        ProtocolData::BigNums(s) => format!("({}\r\n", &s).into_bytes(),
+        ProtocolData::BulkError(b) => {
+            let mut out = format!("!{}\r\n", b.len()).into_bytes();
+            out.extend_from_slice(&b);
+            out.extend_from_slice(b"\r\n");
+            out
+        }
+        ProtocolData::Verbatim(encoding, b) => {
+            let mut out = format!("={}\r\n{}:", b.len() + 4, encoding).into_bytes();
+            out.extend_from_slice(&b);
+            out.extend_from_slice(b"\r\n");
+            out
+        }
+        ProtocolData::Map(pairs) => encode_map_like(b'%', pairs),
+        ProtocolData::Attributes(pairs) => encode_map_like(b'|', pairs),
+    }
+}
+
+/// `f64`'s `Display` renders `NaN`, but RESP3 requires the lowercase `nan`.
+/// `inf`/`-inf` already match `Display`'s output; spelled out anyway so the
+/// wire format doesn't depend on that being a coincidence.
+fn format_double(v: f64) -> String {
+    if v.is_nan() {
+        "nan".to_string()
+    } else if v.is_infinite() {
+        if v.is_sign_negative() {
+            "-inf".to_string()
+        } else {
+            "inf".to_string()
+        }
+    } else {
+        v.to_string()
+    }
+}
+
+fn encode_aggregate(prefix: u8, items: Vec<ProtocolData>) -> Vec<u8> {
+    let mut out = format!("{}{}\r\n", prefix as char, items.len()).into_bytes();
+    for item in items {
+        out.extend(encode_protocol(item));
+    }
+    out
+}
+
+fn encode_map_like(prefix: u8, pairs: Vec<(ProtocolData, ProtocolData)>) -> Vec<u8> {
+    let mut out = format!("{}{}\r\n", prefix as char, pairs.len()).into_bytes();
+    for (key, val) in pairs {
+        out.extend(encode_protocol(key));
+        out.extend(encode_protocol(val));
     }
+    out
 }
 
-fn parse_line(s: &str) -> IResult<&str, &str> {
+fn parse_line(s: &[u8]) -> IResult<&[u8], &[u8]> {
     map((take_until("\r\n"), tag("\r\n")), |(x, _)| x).parse(s)
 }
 
-fn parse_simple_string(s: &str) -> IResult<&str, ProtocolData> {
-    map((tag("+"), parse_line), |(_, x)| {
-        ProtocolData::SimpleString(x.to_string())
+fn parse_len(s: &[u8]) -> IResult<&[u8], usize> {
+    map_res(parse_line, |x| {
+        str::from_utf8(x)
+            .map_err(|e| anyhow!(e))
+            .and_then(|x| usize::from_str_radix(x, 10).map_err(|e| anyhow!(e)))
     })
     .parse(s)
 }
 
-fn parse_simple_error(s: &str) -> IResult<&str, ProtocolData> {
-    map((tag("-"), parse_line), |(_, x)| {
-        ProtocolData::SimpleError(x.to_string())
+fn parse_simple_string(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    map_res((tag("+"), parse_line), |(_, x)| {
+        str::from_utf8(x).map(|x| ProtocolData::SimpleString(x.to_string()))
     })
     .parse(s)
 }
 
-fn parse_integer(s: &str) -> IResult<&str, ProtocolData> {
-    map_res((tag(":"), parse_line), |(_, x)| {
-        i64::from_str_radix(x, 10).map(|v| ProtocolData::Integer(v))
+fn parse_simple_error(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    map_res((tag("-"), parse_line), |(_, x)| {
+        str::from_utf8(x).map(|x| ProtocolData::SimpleError(x.to_string()))
     })
     .parse(s)
 }
 
-fn parse_bulk_string(s: &str) -> IResult<&str, ProtocolData> {
-    map((tag("$"), parse_line, parse_line), |(_, _, x)| {
-        ProtocolData::BulkString(x.to_string())
+fn parse_integer(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    map_res((tag(":"), parse_line), |(_, x)| {
+        str::from_utf8(x)
+            .map_err(|e| anyhow!(e))
+            .and_then(|x| i64::from_str_radix(x, 10).map_err(|e| anyhow!(e)))
+            .map(ProtocolData::Integer)
     })
     .parse(s)
 }
 
-fn parse_array_like(s: &str) -> IResult<&str, Vec<ProtocolData>> {
-    let (s, len) = map_res(parse_line, |x| usize::from_str_radix(x, 10)).parse(s)?;
-    let mut prots = Vec::with_capacity(len);
-    let mut curr = s;
-    for _ in 0..len {
-        let (next, prot) = parse_protocol(curr)?;
-        curr = next;
-        prots.push(prot);
-    }
-    Ok((curr, prots))
+fn parse_bulk_string(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    let (s, _) = tag("$").parse(s)?;
+    let (s, len) = parse_len(s)?;
+    let (s, data) = take(len).parse(s)?;
+    let (s, _) = tag("\r\n").parse(s)?;
+    Ok((s, ProtocolData::BulkString(Bytes::copy_from_slice(data))))
 }
 
-fn parse_array(s: &str) -> IResult<&str, ProtocolData> {
-    map((tag("*"), parse_array_like), |(_, x)| {
-        ProtocolData::Array(x)
+fn parse_bignum(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    map_res((tag("("), parse_line), |(_, x)| {
+        str::from_utf8(x).map(|x| ProtocolData::BigNums(x.to_owned()))
     })
     .parse(s)
 }
 
-fn parse_null(s: &str) -> IResult<&str, ProtocolData> {
+fn parse_null(s: &[u8]) -> IResult<&[u8], ProtocolData> {
     map((tag("_"), tag("\r\n")), |_| ProtocolData::Null).parse(s)
 }
 
-fn parse_boolean(s: &str) -> IResult<&str, ProtocolData> {
-    map_res((tag("#"), one_of("tf")), |(_, c)| match c {
-        't' => Ok(ProtocolData::Boolean(true)),
-        'f' => Ok(ProtocolData::Boolean(false)),
-        _ => Err(anyhow!("Unexpected character '{}' for boolean.", c)),
+fn parse_boolean(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    map_res((tag("#"), take(1usize), tag("\r\n")), |(_, c, _): (_, &[u8], _)| {
+        match c[0] {
+            b't' => Ok(ProtocolData::Boolean(true)),
+            b'f' => Ok(ProtocolData::Boolean(false)),
+            c => Err(anyhow!("Unexpected character '{}' for boolean.", c as char)),
+        }
     })
     .parse(s)
 }
 
-fn parse_doubles(s: &str) -> IResult<&str, ProtocolData> {
+fn parse_doubles(s: &[u8]) -> IResult<&[u8], ProtocolData> {
     map_res((tag(","), parse_line), |(_, x)| {
-        f64::from_str(x).map(ProtocolData::Double)
+        str::from_utf8(x)
+            .map_err(|e| anyhow!(e))
+            .and_then(|x| f64::from_str(x).map_err(|e| anyhow!(e)))
+            .map(ProtocolData::Double)
     })
     .parse(s)
 }
 
-fn parse_bignum(s: &str) -> IResult<&str, ProtocolData> {
-    map((tag("("), parse_line), |(_, x)| {
-        ProtocolData::BigNums(x.to_owned())
-    })
-    .parse(s)
+fn parse_array_like(s: &[u8]) -> IResult<&[u8], Vec<ProtocolData>> {
+    let (s, len) = parse_len(s)?;
+    let mut prots = Vec::with_capacity(len);
+    let mut curr = s;
+    for _ in 0..len {
+        let (next, prot) = parse_protocol(curr)?;
+        curr = next;
+        prots.push(prot);
+    }
+    Ok((curr, prots))
 }
 
-fn parse_bulk_error(s: &str) -> IResult<&str, ProtocolData> {
-    map((tag("!"), parse_line, parse_line), |(_, _, x)| {
-        ProtocolData::BulkString(x.to_string())
+fn parse_array(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    map((tag("*"), parse_array_like), |(_, x)| {
+        ProtocolData::Array(x)
     })
     .parse(s)
 }
 
-fn parse_verbatim(s: &str) -> IResult<&str, ProtocolData> {
-    map(
-        (tag("="), parse_line, take_until(":"), tag(":"), parse_line),
-        |(_, _, encoding, _, data)| ProtocolData::Verbatim(encoding.to_owned(), data.to_owned()),
-    )
-    .parse(s)
+fn parse_bulk_error(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    let (s, _) = tag("!").parse(s)?;
+    let (s, len) = parse_len(s)?;
+    let (s, data) = take(len).parse(s)?;
+    let (s, _) = tag("\r\n").parse(s)?;
+    Ok((s, ProtocolData::BulkError(Bytes::copy_from_slice(data))))
+}
+
+fn parse_verbatim(s: &[u8]) -> IResult<&[u8], ProtocolData> {
+    let (s, _) = tag("=").parse(s)?;
+    let (s, len) = parse_len(s)?;
+    let (s, payload) = take(len).parse(s)?;
+    let (s, _) = tag("\r\n").parse(s)?;
+    if payload.len() < 4 {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            s,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+    let (encoding, rest) = payload.split_at(3);
+    let data = &rest[1..];
+    let encoding = str::from_utf8(encoding)
+        .map_err(|_| nom::Err::Failure(nom::error::Error::new(s, nom::error::ErrorKind::Verify)))?;
+    Ok((
+        s,
+        ProtocolData::Verbatim(encoding.to_owned(), Bytes::copy_from_slice(data)),
+    ))
 }
 
-fn parse_map_like(s: &str) -> IResult<&str, Vec<(ProtocolData, ProtocolData)>> {
-    let (s, entries) = map_res(parse_line, |x| usize::from_str_radix(x, 10)).parse(s)?;
+fn parse_map_like(s: &[u8]) -> IResult<&[u8], Vec<(ProtocolData, ProtocolData)>> {
+    let (s, entries) = parse_len(s)?;
     let mut map = Vec::with_capacity(entries);
     let mut curr = s;
 
     for _ in 0..entries {
-        let (next, tup) = (parse_protocol, parse_protocol).parse(s)?;
+        let (next, tup) = (parse_protocol, parse_protocol).parse(curr)?;
         map.push(tup);
         curr = next;
     }
@@ -165,21 +256,21 @@ fn parse_map_like(s: &str) -> IResult<&str, Vec<(ProtocolData, ProtocolData)>> {
     Ok((curr, map))
 }
 
-fn parse_map(s: &str) -> IResult<&str, ProtocolData> {
+fn parse_map(s: &[u8]) -> IResult<&[u8], ProtocolData> {
     map((tag("%"), parse_map_like), |(_, x)| ProtocolData::Map(x)).parse(s)
 }
 
-fn parse_attributes(s: &str) -> IResult<&str, ProtocolData> {
+fn parse_attributes(s: &[u8]) -> IResult<&[u8], ProtocolData> {
     map((tag("|"), parse_map_like), |(_, x)| {
         ProtocolData::Attributes(x)
     })
     .parse(s)
 }
 
-fn parse_set(s: &str) -> IResult<&str, ProtocolData> {
+fn parse_set(s: &[u8]) -> IResult<&[u8], ProtocolData> {
     map((tag("~"), parse_array_like), |(_, x)| ProtocolData::Set(x)).parse(s)
 }
 
-fn parse_push(s: &str) -> IResult<&str, ProtocolData> {
+fn parse_push(s: &[u8]) -> IResult<&[u8], ProtocolData> {
     map((tag(">"), parse_array_like), |(_, x)| ProtocolData::Push(x)).parse(s)
 }
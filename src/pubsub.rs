@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use crate::protocol::ProtocolData;
+
+pub type SubscriberTx = mpsc::UnboundedSender<ProtocolData>;
+
+static NEXT_SUBSCRIBER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Registry of channel name -> subscriber connections, each reachable
+/// through an unbounded channel into its own writer task. Locking is plain
+/// `std::sync::Mutex` since every critical section is a quick map lookup
+/// with no `.await` inside it.
+#[derive(Default)]
+pub struct Channels {
+    inner: Mutex<HashMap<Arc<str>, HashMap<u64, SubscriberTx>>>,
+}
+
+impl Channels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn next_subscriber_id() -> u64 {
+        NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Registers `tx` for `channel` and returns the channel's subscriber
+    /// count after the registration.
+    pub fn subscribe(&self, channel: Arc<str>, id: u64, tx: SubscriberTx) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let subs = inner.entry(channel).or_default();
+        subs.insert(id, tx);
+        subs.len()
+    }
+
+    /// Removes `id`'s subscription to `channel`, if any, returning the
+    /// channel's remaining subscriber count.
+    pub fn unsubscribe(&self, channel: &str, id: u64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(subs) = inner.get_mut(channel) else {
+            return 0;
+        };
+        subs.remove(&id);
+        let remaining = subs.len();
+        if subs.is_empty() {
+            inner.remove(channel);
+        }
+        remaining
+    }
+
+    /// Drops every subscription `id` holds, across all channels. Connections
+    /// call this once on disconnect.
+    pub fn unsubscribe_all(&self, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.retain(|_, subs| {
+            subs.remove(&id);
+            !subs.is_empty()
+        });
+    }
+
+    /// Fans `message` out to every current subscriber of `channel` as a
+    /// RESP3 Push frame, returning how many subscribers received it.
+    pub fn publish(&self, channel: &str, message: Bytes) -> usize {
+        let inner = self.inner.lock().unwrap();
+        let Some(subs) = inner.get(channel) else {
+            return 0;
+        };
+
+        let push = ProtocolData::Push(vec![
+            ProtocolData::BulkString(Bytes::from_static(b"message")),
+            ProtocolData::BulkString(Bytes::copy_from_slice(channel.as_bytes())),
+            ProtocolData::BulkString(message),
+        ]);
+
+        subs.values()
+            .filter(|tx| tx.send(push.clone()).is_ok())
+            .count()
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    io::ErrorKind,
+    mem::ManuallyDrop,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use evmap::ShallowCopy;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use super::Redis;
+use crate::{
+    command::{Command, Expire, SetCond, parse_command},
+    protocol::{ProtocolData, encode_protocol, parse_protocol},
+    pubsub::{Channels, SubscriberTx},
+    transport::SecureStream,
+};
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub(super) struct Entry {
+    pub(super) val: Arc<[u8]>,
+    pub(super) expire: Option<u64>,
+}
+
+impl ShallowCopy for Entry {
+    unsafe fn shallow_copy(&self) -> ManuallyDrop<Self> {
+        unsafe {
+            ManuallyDrop::new(Self {
+                val: ManuallyDrop::into_inner(self.val.shallow_copy()),
+                expire: ManuallyDrop::into_inner(self.expire.shallow_copy()),
+            })
+        }
+    }
+}
+
+impl Entry {
+    fn expires(&self, curr_ms: u64) -> bool {
+        self.expire.map_or(false, |v| v < curr_ms)
+    }
+}
+
+/// Result of dispatching one parsed command.
+enum Action {
+    Reply(ProtocolData),
+    Subscribe(Vec<Arc<str>>),
+}
+
+fn subscribe_confirmation(channel: &str, count: usize) -> ProtocolData {
+    ProtocolData::Push(vec![
+        ProtocolData::BulkString(Bytes::from_static(b"subscribe")),
+        ProtocolData::BulkString(Bytes::copy_from_slice(channel.as_bytes())),
+        ProtocolData::Integer(count as i64),
+    ])
+}
+
+fn unsubscribe_confirmation(channel: &str, count: usize) -> ProtocolData {
+    ProtocolData::Push(vec![
+        ProtocolData::BulkString(Bytes::from_static(b"unsubscribe")),
+        ProtocolData::BulkString(Bytes::copy_from_slice(channel.as_bytes())),
+        ProtocolData::Integer(count as i64),
+    ])
+}
+
+impl Redis {
+    async fn handle_command(&self, cmd: Command) -> anyhow::Result<ProtocolData> {
+        match cmd {
+            Command::Ping => Ok(ProtocolData::SimpleString("PONG".to_string())),
+            Command::Echo(s) => Ok(ProtocolData::BulkString(s)),
+            Command::Get(s) => match self.reader.handle().get_one(s.as_ref()) {
+                Some(v) => Ok(ProtocolData::BulkString(Bytes::copy_from_slice(&v.val))),
+                None => Ok(ProtocolData::Null),
+            },
+            Command::Set(opts) => {
+                let reader = self.reader.handle();
+                let key = opts.key.clone();
+                let val: Arc<[u8]> = Arc::from(opts.val.as_ref());
+                let mut old_ent = reader.get_one(&key).map(|g| g.as_ref().clone());
+                let unix_time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Time went backwards");
+                let unix_ms = unix_time.as_millis() as u64;
+
+                // Expiry check
+                if old_ent
+                    .as_ref()
+                    .map(|e| e.expires(unix_ms))
+                    .unwrap_or(false)
+                {
+                    // Entry expires, remove entry and invalidate value.
+                    let mut guard = self.writer.lock().await;
+                    guard.remove(key.clone(), old_ent.unwrap()).refresh();
+                    drop(guard);
+                    old_ent = None;
+                }
+
+                match (opts.cond, old_ent.is_some()) {
+                    // Entry exists with NX, NULL
+                    (Some(SetCond::NX), true) => return Ok(ProtocolData::Null),
+                    // Entry not exists with XX, NULL
+                    (Some(SetCond::XX), false) => return Ok(ProtocolData::Null),
+                    _ => {}
+                }
+
+                let mut entry = Entry { val, expire: None };
+                if let Some(exp) = opts.expire {
+                    entry.expire = match exp {
+                        Expire::EX(s) => Some(unix_ms + s * 1000),
+                        Expire::PX(ms) => Some(unix_ms + ms),
+                        Expire::EXAT(s) => Some(s * 1000),
+                        Expire::PXAT(ms) => Some(ms),
+                        Expire::KEEPTTL => old_ent.as_ref().and_then(|e| e.expire),
+                    };
+                } else if let Some(ttl) = self.config.load().default_ttl {
+                    entry.expire = Some(unix_ms + ttl * 1000);
+                }
+                let mut guard = self.writer.lock().await;
+                if let Some(e) = old_ent.as_ref() {
+                    guard.remove(key.clone(), e.clone()).refresh();
+                }
+                guard.insert(key, entry).refresh();
+                drop(guard);
+
+                if opts.ret_old {
+                    Ok(old_ent
+                        .map(|e| ProtocolData::BulkString(Bytes::copy_from_slice(&e.val)))
+                        .unwrap_or(ProtocolData::Null))
+                } else {
+                    Ok(ProtocolData::SimpleString("OK".to_string()))
+                }
+            }
+            Command::Publish { channel, message } => {
+                let count = self.channels.publish(&channel, message);
+                Ok(ProtocolData::Integer(count as i64))
+            }
+            Command::Unsubscribe(_) => {
+                // Reached only when a client unsubscribes without ever
+                // having subscribed on this connection: SUBSCRIBE itself is
+                // intercepted earlier in the read loop, which hands the
+                // connection off to `run_subscriber_plain`/`_secure`, and
+                // that's the only place an already-subscribed connection's
+                // UNSUBSCRIBE is handled.
+                Ok(ProtocolData::Integer(0))
+            }
+            Command::Subscribe(_) => unreachable!(
+                "dispatch() diverts every Ok(Command::Subscribe(..)) before handle_command runs"
+            ),
+        }
+    }
+
+    /// Dispatches a parsed command, diverting `SUBSCRIBE` to `Action::Subscribe`
+    /// since it needs to hand the connection off to a relay loop instead of
+    /// producing a single reply.
+    async fn dispatch(&self, prot: ProtocolData) -> Action {
+        match parse_command(prot) {
+            Ok(Command::Subscribe(channels)) => Action::Subscribe(channels),
+            Ok(cmd) => Action::Reply(match self.handle_command(cmd).await {
+                Ok(prot) => prot,
+                Err(e) => ProtocolData::SimpleError(e.to_string()),
+            }),
+            Err(e) => Action::Reply(ProtocolData::SimpleError(e.to_string())),
+        }
+    }
+
+    /// Handles one parsed command while a connection is in subscriber relay
+    /// mode, returning the reply frame(s) to send back. `subs` is this
+    /// connection's own view of what it's subscribed to, kept in sync with
+    /// the shared `Channels` registry so an empty UNSUBSCRIBE (meaning "all
+    /// channels") knows what to drop.
+    fn process_subscriber_command(
+        &self,
+        prot: ProtocolData,
+        subs: &mut Vec<Arc<str>>,
+        id: u64,
+        tx: &SubscriberTx,
+    ) -> Vec<ProtocolData> {
+        match parse_command(prot) {
+            Ok(Command::Subscribe(new_channels)) => new_channels
+                .into_iter()
+                .map(|channel| {
+                    if !subs.contains(&channel) {
+                        subs.push(channel.clone());
+                    }
+                    let count = self.channels.subscribe(channel.clone(), id, tx.clone());
+                    subscribe_confirmation(&channel, count)
+                })
+                .collect(),
+            Ok(Command::Unsubscribe(targets)) => {
+                let targets = if targets.is_empty() {
+                    subs.clone()
+                } else {
+                    targets
+                };
+                targets
+                    .into_iter()
+                    .map(|channel| {
+                        let count = self.channels.unsubscribe(&channel, id);
+                        subs.retain(|c| *c != channel);
+                        unsubscribe_confirmation(&channel, count)
+                    })
+                    .collect()
+            }
+            Ok(Command::Ping) => vec![ProtocolData::SimpleString("PONG".to_string())],
+            Ok(_) => vec![ProtocolData::SimpleError(
+                "ERR only (UN)SUBSCRIBE and PING are allowed while subscribed".to_string(),
+            )],
+            Err(e) => vec![ProtocolData::SimpleError(e.to_string())],
+        }
+    }
+
+    async fn run_subscriber_plain(
+        &self,
+        channels: Vec<Arc<str>>,
+        stream: &mut TcpStream,
+        mut buf: BytesMut,
+    ) {
+        let id = Channels::next_subscriber_id();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut subs = Vec::with_capacity(channels.len());
+
+        for channel in channels {
+            let count = self.channels.subscribe(channel.clone(), id, tx.clone());
+            let confirm = subscribe_confirmation(&channel, count);
+            subs.push(channel);
+            if let Err(e) = stream.write_all(&encode_protocol(confirm)).await {
+                eprintln!("Failed to write subscribe confirmation: {}", e);
+                self.channels.unsubscribe_all(id);
+                return;
+            }
+        }
+
+        'relay: loop {
+            loop {
+                match parse_protocol(&buf).map_err(|e| e.to_owned()) {
+                    Ok((rest, prot)) => {
+                        let consumed = buf.len() - rest.len();
+                        buf.advance(consumed);
+                        for reply in self.process_subscriber_command(prot, &mut subs, id, &tx) {
+                            if let Err(e) = stream.write_all(&encode_protocol(reply)).await {
+                                eprintln!("Failed to write subscriber reply: {}", e);
+                                break 'relay;
+                            }
+                        }
+                    }
+                    Err(nom::Err::Incomplete(_)) => break,
+                    Err(e) => {
+                        eprintln!("Malformed command while subscribed: {}", e);
+                        break 'relay;
+                    }
+                }
+            }
+
+            tokio::select! {
+                push = rx.recv() => match push {
+                    Some(push) => {
+                        if let Err(e) = stream.write_all(&encode_protocol(push)).await {
+                            eprintln!("Failed to relay published message: {}", e);
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+                readable = stream.readable() => {
+                    if readable.is_err() {
+                        break;
+                    }
+                    match stream.try_read_buf(&mut buf) {
+                        Ok(0) => break,
+                        Ok(_) => {} // loop back around to drain what was just read
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                        Err(_) => break,
+                    }
+                }
+            }
+        }
+
+        self.channels.unsubscribe_all(id);
+    }
+
+    async fn run_subscriber_secure(
+        &self,
+        channels: Vec<Arc<str>>,
+        secure: &mut SecureStream,
+        mut buf: BytesMut,
+    ) {
+        let id = Channels::next_subscriber_id();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut subs = Vec::with_capacity(channels.len());
+
+        for channel in channels {
+            let count = self.channels.subscribe(channel.clone(), id, tx.clone());
+            let confirm = subscribe_confirmation(&channel, count);
+            subs.push(channel);
+            if let Err(e) = secure.write_frame(&encode_protocol(confirm)).await {
+                eprintln!("Failed to write subscribe confirmation: {}", e);
+                self.channels.unsubscribe_all(id);
+                return;
+            }
+        }
+
+        'relay: loop {
+            loop {
+                match parse_protocol(&buf).map_err(|e| e.to_owned()) {
+                    Ok((rest, prot)) => {
+                        let consumed = buf.len() - rest.len();
+                        buf.advance(consumed);
+                        for reply in self.process_subscriber_command(prot, &mut subs, id, &tx) {
+                            if let Err(e) = secure.write_frame(&encode_protocol(reply)).await {
+                                eprintln!("Failed to write subscriber reply: {}", e);
+                                break 'relay;
+                            }
+                        }
+                    }
+                    Err(nom::Err::Incomplete(_)) => break,
+                    Err(e) => {
+                        eprintln!("Malformed command while subscribed: {}", e);
+                        break 'relay;
+                    }
+                }
+            }
+
+            tokio::select! {
+                push = rx.recv() => match push {
+                    Some(push) => {
+                        if let Err(e) = secure.write_frame(&encode_protocol(push)).await {
+                            eprintln!("Failed to relay published message: {}", e);
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+                // As in the plain-TCP relay, this read doubles as both the
+                // dead-peer check and the read side of subscriber-mode
+                // command parsing, except that it yields whole decrypted
+                // frames: a closed or failing socket surfaces as
+                // `read_frame` erroring.
+                frame = secure.read_frame() => {
+                    match frame {
+                        Ok(frame) => buf.extend_from_slice(&frame),
+                        Err(e) => {
+                            eprintln!("Encrypted read failed: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.channels.unsubscribe_all(id);
+    }
+
+    pub async fn handler(&self, stream: TcpStream) {
+        if self.config.load().encryption {
+            match SecureStream::handshake(stream).await {
+                Ok(secure) => self.handler_secure(secure).await,
+                Err(e) => eprintln!("Encrypted handshake failed: {}", e),
+            }
+        } else {
+            self.handler_plain(stream).await;
+        }
+    }
+
+    async fn handler_plain(&self, mut stream: TcpStream) {
+        let mut buf = BytesMut::with_capacity(4096);
+
+        'conn: loop {
+            if let Err(e) = stream.readable().await {
+                eprintln!("Failed to check stream readable: {}", e);
+                break;
+            }
+
+            match stream.try_read_buf(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {
+                    // Drain every complete command already sitting in the buffer
+                    // (e.g. from a pipelined client) before reading again.
+                    loop {
+                        match parse_protocol(&buf).map_err(|e| e.to_owned()) {
+                            Ok((rest, prot)) => {
+                                let consumed = buf.len() - rest.len();
+                                buf.advance(consumed);
+                                match self.dispatch(prot).await {
+                                    Action::Reply(resp) => {
+                                        if let Err(e) =
+                                            stream.write_all(&encode_protocol(resp)).await
+                                        {
+                                            eprintln!("Failed to write response: {}", e);
+                                            break 'conn;
+                                        }
+                                    }
+                                    Action::Subscribe(channels) => {
+                                        // Anything already buffered after the
+                                        // SUBSCRIBE frame (e.g. a pipelined
+                                        // UNSUBSCRIBE) belongs to the relay
+                                        // loop now, not this one.
+                                        let leftover = std::mem::take(&mut buf);
+                                        self.run_subscriber_plain(channels, &mut stream, leftover)
+                                            .await;
+                                        break 'conn;
+                                    }
+                                }
+                            }
+                            Err(nom::Err::Incomplete(_)) => break,
+                            Err(e) => {
+                                eprintln!("Malformed command: {}", e);
+                                break 'conn;
+                            }
+                        }
+                    }
+                }
+                Err(ref e) if (e.kind() == ErrorKind::WouldBlock) => continue,
+                Err(e) => {
+                    eprintln!("Failed to read command: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn handler_secure(&self, mut secure: SecureStream) {
+        let mut buf = BytesMut::new();
+
+        loop {
+            let frame = match secure.read_frame().await {
+                Ok(frame) => frame,
+                Err(e) => {
+                    eprintln!("Encrypted read failed: {}", e);
+                    break;
+                }
+            };
+            buf.extend_from_slice(&frame);
+
+            loop {
+                match parse_protocol(&buf).map_err(|e| e.to_owned()) {
+                    Ok((rest, prot)) => {
+                        let consumed = buf.len() - rest.len();
+                        buf.advance(consumed);
+                        match self.dispatch(prot).await {
+                            Action::Reply(resp) => {
+                                if let Err(e) =
+                                    secure.write_frame(&encode_protocol(resp)).await
+                                {
+                                    eprintln!("Encrypted write failed: {}", e);
+                                    return;
+                                }
+                            }
+                            Action::Subscribe(channels) => {
+                                // Same leftover-buffer handoff as the plain
+                                // path: bytes pipelined after SUBSCRIBE in
+                                // this frame belong to the relay loop.
+                                let leftover = std::mem::take(&mut buf);
+                                self.run_subscriber_secure(channels, &mut secure, leftover)
+                                    .await;
+                                return;
+                            }
+                        }
+                    }
+                    Err(nom::Err::Incomplete(_)) => break,
+                    Err(e) => {
+                        eprintln!("Malformed command: {}", e);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
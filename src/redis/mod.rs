@@ -1,22 +1,52 @@
 mod handler;
+mod persistence;
 
-use std::sync::Arc;
+use std::{io, sync::Arc, time::Duration};
 
+use arc_swap::ArcSwap;
 use evmap::{ReadHandleFactory, WriteHandle};
 use handler::Entry;
 use tokio::sync::Mutex;
 
+use crate::{config::Config, pubsub::Channels};
+
 pub struct Redis {
     reader: ReadHandleFactory<Arc<str>, Entry>,
     writer: Mutex<WriteHandle<Arc<str>, Entry>>,
+    config: Arc<ArcSwap<Config>>,
+    channels: Channels,
 }
 
 impl Redis {
-    pub fn new() -> Self {
-        let (reader, writer) = evmap::new();
+    pub fn new(config: Arc<ArcSwap<Config>>) -> Self {
+        let (reader, mut writer) = evmap::new();
+        let cfg = config.load();
+        let path = persistence::snapshot_path(&cfg);
+
+        if let Err(e) = persistence::load_snapshot(&mut writer, &path) {
+            eprintln!("Failed to load snapshot {}: {}", path.display(), e);
+        }
+
+        let reader = reader.factory();
+        persistence::spawn_snapshot_task(
+            reader.clone(),
+            path,
+            Duration::from_secs(cfg.snapshot_interval_secs),
+        );
+
         Self {
-            reader: reader.factory(),
+            reader,
             writer: Mutex::new(writer),
+            config,
+            channels: Channels::new(),
         }
     }
+
+    /// Writes the keyspace to the configured snapshot file right now,
+    /// instead of waiting for the periodic background task. Intended for
+    /// graceful-shutdown paths.
+    pub fn snapshot_now(&self) -> io::Result<()> {
+        let path = persistence::snapshot_path(&self.config.load());
+        persistence::write_snapshot(&self.reader, &path)
+    }
 }
@@ -0,0 +1,275 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use evmap::{ReadHandleFactory, WriteHandle};
+
+use super::handler::Entry;
+use crate::config::Config;
+
+const MAGIC: &[u8; 4] = b"MRDB";
+const FORMAT_VERSION: u32 = 2;
+
+pub(super) fn snapshot_path(config: &Config) -> PathBuf {
+    config.data_dir.join("dump.mrdb")
+}
+
+/// Serializes the whole keyspace (key, value, absolute expiry in unix-ms) to
+/// `path` atomically: the snapshot is written to a sibling temp file first,
+/// then renamed over the final path, so a crash mid-write never leaves a
+/// corrupt snapshot behind.
+pub(super) fn write_snapshot(
+    reader: &ReadHandleFactory<Arc<str>, Entry>,
+    path: &Path,
+) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+
+    if let Some(map) = reader.handle().read() {
+        for (key, values) in map.iter() {
+            let Some(entry) = values.iter().next() else {
+                continue;
+            };
+            buf.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            buf.extend_from_slice(key.as_bytes());
+            // A presence byte ahead of the expiry value, rather than folding
+            // "no expiry" into 0, so a genuine `expire = Some(0)` entry
+            // doesn't come back from disk as non-expiring.
+            buf.push(entry.expire.is_some() as u8);
+            buf.extend_from_slice(&entry.expire.unwrap_or(0).to_be_bytes());
+            buf.extend_from_slice(&(entry.val.len() as u32).to_be_bytes());
+            buf.extend_from_slice(&entry.val);
+        }
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, &buf)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Replays a snapshot written by `write_snapshot` back into `writer`,
+/// skipping any entry whose absolute expiry has already passed. A missing
+/// file is not an error: it just means there's nothing to restore yet.
+pub(super) fn load_snapshot(
+    writer: &mut WriteHandle<Arc<str>, Entry>,
+    path: &Path,
+) -> io::Result<()> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let mut cursor = &data[..];
+
+    let mut magic = [0u8; 4];
+    cursor.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a my-redis-rs snapshot",
+        ));
+    }
+
+    let mut version_bytes = [0u8; 4];
+    cursor.read_exact(&mut version_bytes)?;
+    let version = u32::from_be_bytes(version_bytes);
+    if version != FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported snapshot format version {}", version),
+        ));
+    }
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64;
+
+    let mut loaded = 0;
+    while !cursor.is_empty() {
+        let key = read_len_prefixed(&mut cursor)?;
+        let key =
+            String::from_utf8(key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut has_expire = [0u8; 1];
+        cursor.read_exact(&mut has_expire)?;
+        let mut expire_bytes = [0u8; 8];
+        cursor.read_exact(&mut expire_bytes)?;
+        let expire = (has_expire[0] != 0).then(|| u64::from_be_bytes(expire_bytes));
+
+        let val = read_len_prefixed(&mut cursor)?;
+
+        if expire.is_some_and(|ms| ms < now_ms) {
+            continue;
+        }
+
+        writer.insert(
+            Arc::from(key),
+            Entry {
+                val: Arc::from(val.as_slice()),
+                expire,
+            },
+        );
+        loaded += 1;
+    }
+    writer.refresh();
+
+    println!("Loaded {} key(s) from snapshot {}", loaded, path.display());
+    Ok(())
+}
+
+fn read_len_prefixed(cursor: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let mut len_bytes = [0u8; 4];
+    cursor.read_exact(&mut len_bytes)?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    let mut buf = vec![0u8; len];
+    cursor.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Spawns a background task that snapshots the keyspace to `path` every
+/// `interval`. A final snapshot should still be written directly (see
+/// `Redis::snapshot_now`) on graceful shutdown. `interval` of zero disables
+/// periodic snapshotting entirely, since `tokio::time::interval` panics on
+/// a zero-duration period.
+pub(super) fn spawn_snapshot_task(
+    reader: ReadHandleFactory<Arc<str>, Entry>,
+    path: PathBuf,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        if interval.is_zero() {
+            println!("snapshot_interval_secs is 0, periodic snapshotting disabled");
+            return;
+        }
+
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await; // first tick fires immediately; skip it
+        loop {
+            ticker.tick().await;
+            if let Err(e) = write_snapshot(&reader, &path) {
+                eprintln!("Failed to write snapshot {}: {}", path.display(), e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    static TEST_DIR_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    /// A fresh scratch directory for a single test, so concurrently-running
+    /// tests don't trip over each other's snapshot files.
+    fn scratch_dir() -> PathBuf {
+        let n = TEST_DIR_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!(
+            "my-redis-rs-persistence-test-{}-{}",
+            std::process::id(),
+            n
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(val: &[u8], expire: Option<u64>) -> Entry {
+        Entry {
+            val: Arc::from(val),
+            expire,
+        }
+    }
+
+    #[test]
+    fn round_trips_keys_with_and_without_expiry() {
+        let dir = scratch_dir();
+        let path = dir.join("dump.mrdb");
+
+        let (reader, mut writer) = evmap::new();
+        writer.insert(Arc::from("no-ttl"), entry(b"a", None));
+        writer.insert(Arc::from("far-future"), entry(b"b", Some(u64::MAX)));
+        writer.refresh();
+
+        write_snapshot(&reader.factory(), &path).unwrap();
+
+        let (reader2, mut writer2) = evmap::new();
+        load_snapshot(&mut writer2, &path).unwrap();
+        let map = reader2.read().unwrap();
+
+        assert_eq!(map.get_one("no-ttl").unwrap().expire, None);
+        assert_eq!(map.get_one("far-future").unwrap().expire, Some(u64::MAX));
+        assert_eq!(map.get_one("far-future").unwrap().val.as_ref(), b"b");
+    }
+
+    #[test]
+    fn load_skips_entries_already_expired() {
+        let dir = scratch_dir();
+        let path = dir.join("dump.mrdb");
+
+        let (reader, mut writer) = evmap::new();
+        writer.insert(Arc::from("expired"), entry(b"stale", Some(1)));
+        // `expire = Some(0)` (expired at the unix epoch) used to be
+        // serialized identically to "no expiry" because the old format
+        // folded both into a bare 0 u64, so reloading it resurrected the
+        // key as permanent instead of dropping it as already-expired.
+        writer.insert(Arc::from("epoch-expiry"), entry(b"old", Some(0)));
+        writer.insert(Arc::from("fresh"), entry(b"current", None));
+        writer.refresh();
+
+        write_snapshot(&reader.factory(), &path).unwrap();
+
+        let (reader2, mut writer2) = evmap::new();
+        load_snapshot(&mut writer2, &path).unwrap();
+        let map = reader2.read().unwrap();
+
+        assert!(map.get_one("expired").is_none());
+        assert!(map.get_one("epoch-expiry").is_none());
+        assert!(map.get_one("fresh").is_some());
+    }
+
+    #[test]
+    fn load_missing_file_is_not_an_error() {
+        let dir = scratch_dir();
+        let path = dir.join("does-not-exist.mrdb");
+
+        let (_, mut writer) = evmap::new::<Arc<str>, Entry>();
+        load_snapshot(&mut writer, &path).unwrap();
+    }
+
+    #[test]
+    fn load_rejects_wrong_magic() {
+        let dir = scratch_dir();
+        let path = dir.join("dump.mrdb");
+        fs::write(&path, b"NOPE0000").unwrap();
+
+        let (_, mut writer) = evmap::new::<Arc<str>, Entry>();
+        let err = load_snapshot(&mut writer, &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn load_rejects_future_format_version() {
+        let dir = scratch_dir();
+        let path = dir.join("dump.mrdb");
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        fs::write(&path, &buf).unwrap();
+
+        let (_, mut writer) = evmap::new::<Arc<str>, Entry>();
+        let err = load_snapshot(&mut writer, &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}